@@ -0,0 +1,46 @@
+use anchor_lang::prelude::*;
+
+#[error_code]
+pub enum SettlementError {
+    #[msg("Oracle price must be positive")]
+    InvalidOraclePrice,
+
+    #[msg("Entry price must be positive")]
+    InvalidEntryPrice,
+
+    #[msg("Calculation resulted in overflow or underflow")]
+    CalculationOverflow,
+
+    #[msg("Oracle confidence interval is too wide relative to price")]
+    OracleConfidenceExceeded,
+
+    #[msg("Oracle price is too stale to settle against")]
+    OracleStale,
+
+    #[msg("Account health is insufficient for this settlement or withdrawal")]
+    HealthInsufficient,
+
+    #[msg("Account maintenance health is not below zero; not liquidatable")]
+    NotLiquidatable,
+
+    #[msg("Position has no one-shot or recurring settle budget available")]
+    SettleLimitExceeded,
+
+    #[msg("Account has no free position slot left to open a new market")]
+    NoFreePositionSlot,
+
+    #[msg("No open position for this market index")]
+    PositionNotFound,
+
+    #[msg("Remaining accounts did not match the account's active positions")]
+    MarketAccountMismatch,
+
+    #[msg("Account is not bankrupt: collateral is non-negative or a position is still open")]
+    NotBankrupt,
+
+    #[msg("Insurance fund account does not match this market's configured insurance fund")]
+    InsuranceFundMismatch,
+
+    #[msg("No recently liquidated position recorded for this market to derive a haircut side")]
+    BankruptcySideUnknown,
+}
@@ -0,0 +1,34 @@
+use anchor_lang::prelude::*;
+use fixed::types::I80F48;
+
+#[event]
+pub struct SettlementEvent {
+    pub account_key: Pubkey,
+    pub market_index: u16,
+    pub oracle_price: I80F48,
+    pub unrealized_pnl: I80F48,
+    pub funding_payment: I80F48,
+    pub net_settlement: I80F48,
+    pub new_collateral: i128,
+}
+
+#[event]
+pub struct LiquidationEvent {
+    pub account_key: Pubkey,
+    pub market_index: u16,
+    pub liquidator: Pubkey,
+    pub base_amount: u64,
+    pub mark_price: I80F48,
+    pub transfer_to_liquidator: I80F48,
+    pub new_size: i64,
+    pub new_collateral: i128,
+}
+
+#[event]
+pub struct BankruptcyResolvedEvent {
+    pub account_key: Pubkey,
+    pub market_index: u16,
+    pub deficit: i128,
+    pub insurance_used: i128,
+    pub socialized_amount: i128,
+}
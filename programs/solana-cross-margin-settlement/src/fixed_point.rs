@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+use fixed::types::I80F48;
+
+use crate::errors::SettlementError;
+
+/// Rounds an `I80F48` value to the nearest integer, for call sites (e.g.
+/// events or downstream integer balances) that want an integer view of an
+/// otherwise fractional-precision value without silently truncating it.
+pub fn round_to_i128(value: I80F48) -> Result<i128> {
+    value
+        .round()
+        .checked_to_num::<i128>()
+        .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+}
+
+/// Clamps `value` to within `[-limit, limit]` while preserving its sign,
+/// for shrinking a signed amount down to an available budget.
+pub fn clamp_magnitude(value: I80F48, limit: I80F48) -> Result<I80F48> {
+    let bound = limit.checked_abs().ok_or(SettlementError::CalculationOverflow)?;
+    Ok(value.clamp(bound.checked_neg().ok_or(SettlementError::CalculationOverflow)?, bound))
+}
+
+/// Converts an i128 account field (e.g. `collateral`, a funding snapshot, a
+/// settle-limit budget) into `I80F48`. `I80F48::from_num` only panics on
+/// out-of-range input in debug builds; in release it silently wraps, which
+/// would let an ever-accumulating i128 field (unbounded, unlike a single
+/// settlement's I80F48 intermediates) corrupt a health or bankruptcy
+/// calculation instead of tripping `CalculationOverflow`. Always go through
+/// `checked_from_num` for this direction.
+pub fn checked_from_i128(value: i128) -> Result<I80F48> {
+    I80F48::checked_from_num(value).ok_or_else(|| error!(SettlementError::CalculationOverflow))
+}
@@ -0,0 +1,184 @@
+use anchor_lang::prelude::*;
+use fixed::types::I80F48;
+
+use crate::errors::SettlementError;
+
+/// Weights a signed native value by the asset weight when it's a net asset
+/// (>= 0) or the liability weight when it's a net liability (< 0).
+fn weighted(value: I80F48, asset_weight: I80F48, liab_weight: I80F48) -> Result<I80F48> {
+    let weight = if value.is_negative() {
+        liab_weight
+    } else {
+        asset_weight
+    };
+    value
+        .checked_mul(weight)
+        .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+}
+
+/// Weighted notional for a single position, for callers (e.g. a multi-market
+/// account health sum) that weight each position against its own market's
+/// weights but want the shared collateral counted only once, unweighted.
+pub fn weighted_position_notional(
+    size: i64,
+    mark_price: I80F48,
+    asset_weight: I80F48,
+    liab_weight: I80F48,
+) -> Result<I80F48> {
+    let notional = I80F48::from_num(size)
+        .checked_mul(mark_price)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    weighted(notional, asset_weight, liab_weight)
+}
+
+/// Computes account health = weighted collateral + weighted position notional
+/// + `other_weighted_notional`.
+///
+/// `health < 0` means the account is undercollateralized under the given
+/// weight set (pass `init_*` weights to gate new settlements/withdrawals, or
+/// `maint_*` weights to decide whether the account can be liquidated).
+///
+/// `other_weighted_notional` is the sum of every other active position's own
+/// `weighted_position_notional` on a multi-market account (zero for an
+/// account with no other exposure) - callers with several concurrent
+/// positions (e.g. `liquidate`) must fold those in here rather than judging
+/// liquidatability off a single market in isolation.
+pub fn compute_health(
+    collateral: I80F48,
+    size: i64,
+    mark_price: I80F48,
+    asset_weight: I80F48,
+    liab_weight: I80F48,
+    other_weighted_notional: I80F48,
+) -> Result<I80F48> {
+    let notional = I80F48::from_num(size)
+        .checked_mul(mark_price)
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    let weighted_collateral = weighted(collateral, asset_weight, liab_weight)?;
+    let weighted_notional = weighted(notional, asset_weight, liab_weight)?;
+
+    weighted_collateral
+        .checked_add(weighted_notional)
+        .and_then(|health| health.checked_add(other_weighted_notional))
+        .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+}
+
+/// The result of reducing a position by `base_amount` during liquidation.
+pub struct LiquidationStep {
+    pub base_amount: u64,
+    /// Liquidation fee paid to the liquidator. The closed notional's own
+    /// realized PnL is not part of this - it's already folded into
+    /// `new_collateral`, since it's the liquidated account's gain or loss,
+    /// not the liquidator's.
+    pub transfer_to_liquidator: I80F48,
+    pub new_size: i64,
+    pub new_collateral: I80F48,
+}
+
+fn liquidation_step(
+    position_size: i64,
+    entry_price: I80F48,
+    collateral: I80F48,
+    mark_price: I80F48,
+    liquidation_fee: I80F48,
+    base_amount: u64,
+) -> Result<LiquidationStep> {
+    let sign: i64 = if position_size > 0 { 1 } else { -1 };
+    let q = I80F48::from_num(base_amount);
+
+    // PnL realized by closing `base_amount` units at the mark price.
+    let closed_pnl = I80F48::from_num(sign)
+        .checked_mul(q)
+        .and_then(|v| v.checked_mul(mark_price.checked_sub(entry_price)?))
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    // Liquidator incentive, paid on top of (not out of) the realized PnL
+    // above - the account being liquidated keeps its own realized loss or
+    // gain from closing at the mark price, and the liquidator is only ever
+    // compensated this fee for performing the liquidation.
+    let notional = q
+        .checked_mul(mark_price)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    let fee = notional
+        .checked_mul(liquidation_fee)
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    let transfer_to_liquidator = fee;
+
+    let size_delta = sign
+        .checked_mul(base_amount as i64)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    let new_size = position_size
+        .checked_sub(size_delta)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    let new_collateral = collateral
+        .checked_add(closed_pnl)
+        .and_then(|c| c.checked_sub(fee))
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    Ok(LiquidationStep {
+        base_amount,
+        transfer_to_liquidator,
+        new_size,
+        new_collateral,
+    })
+}
+
+/// Finds the smallest `base_amount` (bounded by `max_base_amount`) whose
+/// resulting maintenance health is already `>= 0`, so a liquidation never
+/// reduces a position further than strictly necessary to make it whole.
+/// Falls back to `max_base_amount` if that's still not enough.
+///
+/// `other_weighted_notional` is forwarded to `compute_health` unchanged at
+/// every candidate step - reducing this market's position doesn't change
+/// what the account holds elsewhere.
+#[allow(clippy::too_many_arguments)]
+pub fn find_liquidation_amount(
+    position_size: i64,
+    entry_price: I80F48,
+    collateral: I80F48,
+    mark_price: I80F48,
+    liquidation_fee: I80F48,
+    maint_asset_weight: I80F48,
+    maint_liab_weight: I80F48,
+    other_weighted_notional: I80F48,
+    max_base_amount: u64,
+) -> Result<LiquidationStep> {
+    let mut lo: u64 = 0;
+    let mut hi: u64 = max_base_amount;
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let candidate = liquidation_step(
+            position_size,
+            entry_price,
+            collateral,
+            mark_price,
+            liquidation_fee,
+            mid,
+        )?;
+        let health = compute_health(
+            candidate.new_collateral,
+            candidate.new_size,
+            mark_price,
+            maint_asset_weight,
+            maint_liab_weight,
+            other_weighted_notional,
+        )?;
+        if health >= I80F48::ZERO {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    liquidation_step(
+        position_size,
+        entry_price,
+        collateral,
+        mark_price,
+        liquidation_fee,
+        lo,
+    )
+}
@@ -1,206 +1,618 @@
 use anchor_lang::prelude::*;
+use fixed::types::I80F48;
+
+pub mod errors;
+pub mod events;
+pub mod fixed_point;
+pub mod health;
+pub mod oracle;
+pub mod state;
+
+pub use errors::*;
+pub use events::*;
+pub use fixed_point::*;
+pub use oracle::*;
+pub use state::*;
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
+/// Reads and validates the oracle per `market.oracle_config` (confidence band,
+/// staleness), then folds it into `market.stable_price_model`. Returns
+/// `(raw_oracle_price, stable_price)`. Shared by every instruction that needs
+/// a trusted mark price.
+fn read_validated_mark_price(market: &mut Market, oracle_ai: &AccountInfo) -> Result<(I80F48, I80F48)> {
+    let oracle_price_data = oracle::read_oracle_price(oracle_ai, &market.oracle_config)?;
+    require!(
+        oracle_price_data.price.is_positive(),
+        SettlementError::InvalidOraclePrice
+    );
+
+    let conf_ratio = oracle_price_data
+        .confidence
+        .checked_div(oracle_price_data.price)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    require!(
+        conf_ratio <= market.oracle_config.conf_filter,
+        SettlementError::OracleConfidenceExceeded
+    );
+
+    let current_slot = Clock::get()?.slot;
+    let staleness_slots = current_slot.saturating_sub(oracle_price_data.slot);
+    require!(
+        staleness_slots <= market.oracle_config.max_staleness_slots,
+        SettlementError::OracleStale
+    );
+
+    let now_ts = Clock::get()?.unix_timestamp;
+    let stable_price = market
+        .stable_price_model
+        .update(oracle_price_data.price, now_ts)?;
+    Ok((oracle_price_data.price, stable_price))
+}
+
+/// The per-position outcome of folding one market's settlement into a
+/// `CrossMarginAccount`, returned by `settle_position` for the caller to fold
+/// into shared collateral and an event.
+pub struct PositionSettlement {
+    pub oracle_price: I80F48,
+    pub stable_price: I80F48,
+    pub unrealized_pnl: I80F48,
+    pub funding_payment: I80F48,
+    pub net_settlement: I80F48,
+}
+
+/// Settles a single `PerpPosition`'s unrealized PnL and funding against its
+/// `Market`, applying the one-shot/recurring settle-limit clamps and
+/// advancing funding/entry-price checkpoints. Funding is charged from
+/// `market.{long,short}_funding_index`, a protocol-maintained cumulative
+/// index, rather than a per-call caller-supplied rate -
+/// this is what lets `settle_cross_margin` loop over many positions without
+/// every caller having to agree on and pass a funding rate per market.
+fn settle_position(
+    position: &mut PerpPosition,
+    market: &mut Market,
+    oracle_ai: &AccountInfo,
+) -> Result<PositionSettlement> {
+    // 1. Read the oracle's published price, confidence interval, and last
+    // update slot directly from the account rather than trusting caller
+    // input, then fold it into the smoothed model so a single bad print
+    // can't move `account.collateral` by more than its growth limits allow.
+    let (oracle_price, stable_price) = read_validated_mark_price(market, oracle_ai)?;
+    settle_position_at_price(position, market, oracle_price, stable_price)
+}
+
+/// The settle-limit-clamped core of `settle_position`, taking an
+/// already-validated `(oracle_price, stable_price)` pair instead of reading
+/// them off a live oracle account. Split out so it's directly exercisable in
+/// tests without needing a real Pyth/Switchboard account.
+pub fn settle_position_at_price(
+    position: &mut PerpPosition,
+    market: &mut Market,
+    oracle_price: I80F48,
+    stable_price: I80F48,
+) -> Result<PositionSettlement> {
+    require!(position.entry_price.is_positive(), SettlementError::InvalidEntryPrice);
+
+    if position.size == 0 {
+        // No position to settle, but fast-forward both funding checkpoints
+        // so a later open against this market doesn't inherit a stale index.
+        position.cumulative_funding_long = round_to_i128(market.long_funding_index)?;
+        position.cumulative_funding_short = round_to_i128(market.short_funding_index)?;
+        return Ok(PositionSettlement {
+            oracle_price,
+            stable_price,
+            unrealized_pnl: I80F48::ZERO,
+            funding_payment: I80F48::ZERO,
+            net_settlement: I80F48::ZERO,
+        });
+    }
+
+    // ============================================================================
+    // UNREALIZED PnL CALCULATION
+    // ============================================================================
+
+    let price_delta = stable_price
+        .checked_sub(position.entry_price)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    let unrealized_pnl = price_delta
+        .checked_mul(I80F48::from_num(position.size))
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    // ============================================================================
+    // FUNDING PAYMENT CALCULATION (CUMULATIVE INDEX)
+    // ============================================================================
+
+    // Longs are charged off `long_funding_index`, shorts off
+    // `short_funding_index`; only the side this position is actually on can
+    // owe anything this call.
+    let (current_index, snapshot) = if position.size > 0 {
+        (market.long_funding_index, checked_from_i128(position.cumulative_funding_long)?)
+    } else {
+        (market.short_funding_index, checked_from_i128(position.cumulative_funding_short)?)
+    };
+    let funding_delta = current_index
+        .checked_sub(snapshot)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    let funding_payment = funding_delta
+        .checked_mul(I80F48::from_num(position.size))
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    // ============================================================================
+    // SETTLE LIMITS (ONE-SHOT FUNDING vS RECURRING MARK-TO-MARKET)
+    // ============================================================================
+
+    let notional = stable_price
+        .checked_abs()
+        .and_then(|p| p.checked_mul(I80F48::from_num(position.size.unsigned_abs())))
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    // There's no separate open/increase-position instruction in this program,
+    // so a position whose size doesn't match the snapshot from its last
+    // refresh - including one straight off `PerpPosition::default()`, whose
+    // budgets start at zero - was just opened or resized and is due a fresh
+    // budget sized to its current notional, rather than being stuck at
+    // whatever the downward-only ratchet below last left it at.
+    if position.settle_limit_size != position.size {
+        position.recurring_settle_limit = round_to_i128(notional)?;
+        position.oneshot_settle_limit = round_to_i128(notional)?;
+        position.settle_limit_size = position.size;
+    }
+
+    // Funding is one-shot-settleable: it's eligible to settle in full the
+    // instant it's owed, up to whatever budget remains.
+    let oneshot_available = checked_from_i128(position.oneshot_settle_limit)?;
+    let settled_funding = clamp_magnitude(funding_payment, oneshot_available)?;
+
+    // Mark-to-market PnL is only recurring-settleable: the budget can
+    // never exceed the notional still backing the position, and is
+    // refreshed downward (never up) to whatever's still actually
+    // unrealized, rather than tracked as a separate signed ledger that
+    // could drift out of sync on a sign flip.
+    let unsettled_pnl = unrealized_pnl.checked_abs().ok_or(SettlementError::CalculationOverflow)?;
+    let recurring_cap = notional.min(unsettled_pnl);
+    if checked_from_i128(position.recurring_settle_limit)? > recurring_cap {
+        position.recurring_settle_limit = round_to_i128(recurring_cap)?;
+    }
+    let recurring_available = checked_from_i128(position.recurring_settle_limit)?;
+    let settled_pnl = clamp_magnitude(unrealized_pnl, recurring_available)?;
+
+    require!(
+        settled_funding != I80F48::ZERO || funding_payment == I80F48::ZERO,
+        SettlementError::SettleLimitExceeded
+    );
+    require!(
+        settled_pnl != I80F48::ZERO || unrealized_pnl == I80F48::ZERO,
+        SettlementError::SettleLimitExceeded
+    );
+
+    // Net settlement = settled PnL - settled funding, each independently
+    // clamped to its own bucket above, so the total never exceeds the
+    // combined one-shot + recurring budget.
+    // If size > 0 (long): positive PnL increases collateral, positive funding decreases it
+    // If size < 0 (short): negative PnL increases collateral, negative funding decreases it
+    let net_settlement = settled_pnl
+        .checked_sub(settled_funding)
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    // ============================================================================
+    // STATE UPDATES (PREVENT DOUBLE-COUNTING)
+    // ============================================================================
+
+    // Only advance a checkpoint once its side has settled in full; a
+    // partially-limited settlement leaves the unpaid delta live so the
+    // next call recomputes it (and tries again) rather than losing it.
+    if settled_pnl == unrealized_pnl {
+        position.entry_price = stable_price; // Mark-to-market against the stable price
+    }
+    if settled_funding == funding_payment {
+        if position.size > 0 {
+            position.cumulative_funding_long = round_to_i128(current_index)?;
+        } else {
+            position.cumulative_funding_short = round_to_i128(current_index)?;
+        }
+    }
+    // The side this position isn't on can never owe anything, so its
+    // checkpoint is always safe to fast-forward - this keeps a later flip to
+    // that side from being charged funding accrued while it was inactive.
+    if position.size > 0 {
+        position.cumulative_funding_short = round_to_i128(market.short_funding_index)?;
+    } else {
+        position.cumulative_funding_long = round_to_i128(market.long_funding_index)?;
+    }
+
+    position.realized_pnl_native = position
+        .realized_pnl_native
+        .checked_add(round_to_i128(settled_pnl)?)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    position.oneshot_settle_limit = position
+        .oneshot_settle_limit
+        .checked_sub(round_to_i128(settled_funding.abs())?)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    position.recurring_settle_limit = position
+        .recurring_settle_limit
+        .checked_sub(round_to_i128(settled_pnl.abs())?)
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    Ok(PositionSettlement {
+        oracle_price,
+        stable_price,
+        unrealized_pnl,
+        funding_payment,
+        net_settlement,
+    })
+}
+
 #[program]
 pub mod solana_cross_margin_settlement {
     use super::*;
 
-    /// Settles cross-margin positions by applying unrealized PnL and funding payments
+    /// Settles every active position on `account` against its market, netting
+    /// all PnL and funding into the account's one shared `collateral`.
     ///
-    /// # Arguments
-    /// * `oracle_price` - Current oracle price (may be stale/unreliable)
-    /// * `funding_rate` - Current funding rate (signed, per position unit)
+    /// # Remaining accounts
+    /// For each active slot in `account.positions`, in slot order, callers
+    /// must append that position's `(market, oracle)` account pair to
+    /// `ctx.remaining_accounts` - i.e. `2 * account.in_use_count` accounts.
     ///
     /// # Safety Considerations
-    /// - Oracle price may be stale, delayed, or incorrect
-    /// - Funding rate may lag price updates
+    /// - The mark price for each market is read directly from its oracle
+    ///   account (never trusted as caller input) and rejected if its
+    ///   confidence band or staleness, per `market.oracle_config`, is out of bounds
+    /// - PnL is then marked against `market.stable_price_model`'s smoothed price
+    ///   rather than the raw oracle print
+    /// - Funding is charged off each market's cumulative `long_funding_index` /
+    ///   `short_funding_index`, not a per-call caller-supplied rate
     /// - Settlement can be called multiple times
     /// - Must prevent integer overflow/underflow
     /// - Must prevent double-counting of PnL or funding
-    pub fn settle_cross_margin(
-        ctx: Context<SettleCrossMargin>,
-        oracle_price: i64,
-        funding_rate: i64,
-    ) -> Result<()> {
-        let position = &mut ctx.accounts.position;
-        let balance = &mut ctx.accounts.balance;
-
-        // ============================================================================
-        // VALIDATIONS
-        // ============================================================================
-
-        // 1. Validate oracle price is positive (can't have negative prices)
-        require!(oracle_price > 0, SettlementError::InvalidOraclePrice);
-
-        // 2. Validate entry price is positive (sanity check on position state)
-        require!(position.entry_price > 0, SettlementError::InvalidEntryPrice);
-
-        // 3. Check if position has size (no point settling empty position)
-        if position.size == 0 {
-            // No position to settle, but update funding rate to prevent future issues
-            position.last_funding_rate = funding_rate;
-            return Ok(());
-        }
+    pub fn settle_cross_margin(ctx: Context<SettleCrossMargin>) -> Result<()> {
+        // Captured up front: `.key()` needs the `Account` wrapper, while the
+        // loop below works on the deref'd `CrossMarginAccount` so it can
+        // borrow `positions` and `collateral` as separate fields.
+        let account_key = ctx.accounts.account.key();
+        let account: &mut CrossMarginAccount = &mut ctx.accounts.account;
+        let remaining = ctx.remaining_accounts;
 
-        // 4. Validate funding rate is within reasonable bounds to prevent overflow
-        // This is a safety check - in production, you'd have protocol-specific bounds
-        const MAX_FUNDING_RATE: i64 = i64::MAX / 1_000_000; // Arbitrary reasonable bound
         require!(
-            funding_rate.abs() <= MAX_FUNDING_RATE,
-            SettlementError::FundingRateOutOfBounds
+            remaining.len() == account.in_use_count as usize * 2,
+            SettlementError::MarketAccountMismatch
         );
+
+        // Weighted collateral is counted once, unweighted, since cash isn't a
+        // market exposure; each position's notional is weighted by its own
+        // market's weights and summed in.
+        let mut weighted_notional_sum = I80F48::ZERO;
+        let mut pair_idx = 0usize;
+
+        for position in account.positions.iter_mut() {
+            if !position.is_active() {
+                continue;
+            }
+
+            let market_ai = &remaining[pair_idx * 2];
+            let oracle_ai = &remaining[pair_idx * 2 + 1];
+            pair_idx += 1;
+
+            let mut market: Account<Market> = Account::try_from(market_ai)?;
+            require!(
+                market.market_index == position.market_index,
+                SettlementError::MarketAccountMismatch
+            );
+            require!(
+                oracle_ai.key() == market.oracle,
+                SettlementError::InvalidOraclePrice
+            );
+
+            let settlement = settle_position(position, &mut market, oracle_ai)?;
+
+            account.collateral = account
+                .collateral
+                .checked_add(round_to_i128(settlement.net_settlement)?)
+                .ok_or(SettlementError::CalculationOverflow)?;
+
+            weighted_notional_sum = weighted_notional_sum
+                .checked_add(health::weighted_position_notional(
+                    position.size,
+                    settlement.stable_price,
+                    market.init_asset_weight,
+                    market.init_liab_weight,
+                )?)
+                .ok_or(SettlementError::CalculationOverflow)?;
+
+            emit!(SettlementEvent {
+                account_key,
+                market_index: position.market_index,
+                oracle_price: settlement.oracle_price,
+                unrealized_pnl: settlement.unrealized_pnl,
+                funding_payment: settlement.funding_payment,
+                net_settlement: settlement.net_settlement,
+                new_collateral: account.collateral,
+            });
+
+            market.exit(ctx.program_id)?;
+        }
+
+        // Block this settlement if it would leave the account's init health
+        // (the stricter of the two weight sets) below zero. A negative
+        // balance is still allowed in cross-margin as long as it's backed by
+        // enough weighted position notional across all of its markets.
+        let init_health = checked_from_i128(account.collateral)?
+            .checked_add(weighted_notional_sum)
+            .ok_or(SettlementError::CalculationOverflow)?;
+        require!(init_health >= I80F48::ZERO, SettlementError::HealthInsufficient);
+
+        Ok(())
+    }
+
+    /// Liquidates up to `base_amount` of `account`'s position in `market_index`
+    /// whose maintenance health is below zero. The closed notional's realized
+    /// PnL stays with `account` (it's the account's own gain or loss from
+    /// closing at the mark price); only a liquidation fee is transferred to
+    /// the liquidator as compensation for performing the liquidation.
+    /// Stops exactly at the smallest amount that brings maintenance health
+    /// back to `>= 0`.
+    ///
+    /// # Remaining accounts
+    /// For every other active slot in `account.positions` (i.e. every active
+    /// position besides `market_index`), callers must append that position's
+    /// `Market` account to `ctx.remaining_accounts`, in slot order. Their
+    /// already-settled `stable_price_model.stable_price` and maintenance
+    /// weights are folded into the account's health the same way
+    /// `settle_cross_margin` sums weighted notional across markets, since a
+    /// cross-margin account's liquidatability can't be judged off a single
+    /// market in isolation.
+    pub fn liquidate(ctx: Context<Liquidate>, market_index: u16, base_amount: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let account = &mut ctx.accounts.account;
+        let liquidator_account = &mut ctx.accounts.liquidator_account;
+        let remaining = ctx.remaining_accounts;
+
         require!(
-            position.last_funding_rate.abs() <= MAX_FUNDING_RATE,
-            SettlementError::FundingRateOutOfBounds
+            market.market_index == market_index,
+            SettlementError::MarketAccountMismatch
         );
 
-        // ============================================================================
-        // UNREALIZED PnL CALCULATION
-        // ============================================================================
+        let (position_size, entry_price) = {
+            let position = account
+                .position_mut(market_index)
+                .ok_or(SettlementError::PositionNotFound)?;
+            require!(position.size != 0, SettlementError::NotLiquidatable);
+            (position.size, position.entry_price)
+        };
+        require!(base_amount > 0, SettlementError::NotLiquidatable);
+
+        let (_, mark_price) = read_validated_mark_price(market, &ctx.accounts.oracle)?;
+        let collateral = checked_from_i128(account.collateral)?;
+
+        // Fold in every other active position's already-settled notional,
+        // under its own market's maintenance weights, the same way
+        // `settle_cross_margin` sums weighted notional across markets - a
+        // multi-market account can be healthy (or unhealthy) once its other
+        // exposure is counted even though this single market's position
+        // looks underwater (or vice versa).
+        let other_active = account
+            .positions
+            .iter()
+            .filter(|p| p.is_active() && p.market_index != market_index)
+            .count();
+        require!(remaining.len() == other_active, SettlementError::MarketAccountMismatch);
+
+        let mut other_weighted_notional = I80F48::ZERO;
+        let mut other_idx = 0usize;
+        for position in account.positions.iter() {
+            if !position.is_active() || position.market_index == market_index {
+                continue;
+            }
+
+            let other_market: Account<Market> = Account::try_from(&remaining[other_idx])?;
+            require!(
+                other_market.market_index == position.market_index,
+                SettlementError::MarketAccountMismatch
+            );
+            other_idx += 1;
+
+            other_weighted_notional = other_weighted_notional
+                .checked_add(health::weighted_position_notional(
+                    position.size,
+                    other_market.stable_price_model.stable_price,
+                    other_market.maint_asset_weight,
+                    other_market.maint_liab_weight,
+                )?)
+                .ok_or(SettlementError::CalculationOverflow)?;
+        }
 
-        // Calculate price delta with overflow protection
-        let price_delta = oracle_price
-            .checked_sub(position.entry_price)
-            .ok_or(SettlementError::CalculationOverflow)?;
+        let maint_health = health::compute_health(
+            collateral,
+            position_size,
+            mark_price,
+            market.maint_asset_weight,
+            market.maint_liab_weight,
+            other_weighted_notional,
+        )?;
+        require!(maint_health < I80F48::ZERO, SettlementError::NotLiquidatable);
+
+        let max_base_amount = base_amount.min(position_size.unsigned_abs());
+        let step = health::find_liquidation_amount(
+            position_size,
+            entry_price,
+            collateral,
+            mark_price,
+            market.liquidation_fee,
+            market.maint_asset_weight,
+            market.maint_liab_weight,
+            other_weighted_notional,
+            max_base_amount,
+        )?;
+
+        account
+            .position_mut(market_index)
+            .ok_or(SettlementError::PositionNotFound)?
+            .size = step.new_size;
+        account.collateral = round_to_i128(step.new_collateral)?;
+
+        // Record which side this position was on before it's closed and its
+        // slot freed, so `resolve_bankruptcy` can later derive which side to
+        // haircut from data instead of trusting caller-supplied input.
+        if step.new_size == 0 {
+            account.last_closed_market_index = market_index;
+            account.last_closed_was_long = position_size > 0;
+        }
+        account.close_position_if_flat(market_index)?;
 
-        // Calculate unrealized PnL: (oracle_price - entry_price) * size
-        // Use i128 to prevent overflow during multiplication
-        let unrealized_pnl = (price_delta as i128)
-            .checked_mul(position.size as i128)
+        liquidator_account.collateral = liquidator_account
+            .collateral
+            .checked_add(round_to_i128(step.transfer_to_liquidator)?)
             .ok_or(SettlementError::CalculationOverflow)?;
 
-        // ============================================================================
-        // FUNDING PAYMENT CALCULATION
-        // ============================================================================
+        emit!(LiquidationEvent {
+            account_key: account.key(),
+            market_index,
+            liquidator: ctx.accounts.liquidator.key(),
+            base_amount: step.base_amount,
+            mark_price,
+            transfer_to_liquidator: step.transfer_to_liquidator,
+            new_size: step.new_size,
+            new_collateral: account.collateral,
+        });
 
-        // Calculate funding delta (only pay funding accrued since last settlement)
-        // This prevents double-counting of funding
-        let funding_delta = funding_rate
-            .checked_sub(position.last_funding_rate)
-            .ok_or(SettlementError::CalculationOverflow)?;
+        Ok(())
+    }
 
-        // Calculate funding payment: (funding_rate - last_funding_rate) * size
-        // Positive funding_rate means longs pay shorts (reduces long collateral)
-        // Use i128 to prevent overflow
-        let funding_payment = (funding_delta as i128)
-            .checked_mul(position.size as i128)
-            .ok_or(SettlementError::CalculationOverflow)?;
+    /// Resolves a bankrupt `account` (negative collateral with nothing left
+    /// to liquidate) against `market_index`: first draws down the market's
+    /// `InsuranceFund`, then socializes whatever the fund can't cover by
+    /// raising `market`'s `long_funding_index` (longs pay more) or lowering
+    /// its `short_funding_index` (shorts receive less), for whichever side was
+    /// left owed by the account's bankrupt position, so that side's solvent positions settle for proportionally less on
+    /// their next call into `settle_cross_margin`.
+    ///
+    /// Which side to haircut is derived from `account.last_closed_was_long`,
+    /// the sign `liquidate` recorded for this market just before zeroing the
+    /// position out, rather than trusted as caller input - a bankrupt
+    /// account's own state, not a keeper's say-so, decides who gets
+    /// socialized.
+    ///
+    /// Gated behind `market.group_insurance_fund` so insurance coverage must
+    /// be explicitly enabled per market.
+    pub fn resolve_bankruptcy(ctx: Context<ResolveBankruptcy>, market_index: u16) -> Result<()> {
+        let account = &mut ctx.accounts.account;
+        let market = &mut ctx.accounts.market;
+        let insurance_fund = &mut ctx.accounts.insurance_fund;
 
-        // ============================================================================
-        // COLLATERAL UPDATE (CROSS-MARGIN)
-        // ============================================================================
+        require!(
+            market.market_index == market_index,
+            SettlementError::MarketAccountMismatch
+        );
+        require!(market.group_insurance_fund, SettlementError::InsuranceFundMismatch);
 
-        // Net settlement = PnL - funding_payment
-        // If size > 0 (long): positive PnL increases collateral, positive funding decreases it
-        // If size < 0 (short): negative PnL increases collateral, negative funding decreases it
-        let net_settlement = unrealized_pnl
-            .checked_sub(funding_payment)
-            .ok_or(SettlementError::CalculationOverflow)?;
+        require!(account.collateral < 0, SettlementError::NotBankrupt);
+        require!(
+            account.positions.iter().all(|p| !p.is_active() || p.size == 0),
+            SettlementError::NotBankrupt
+        );
+        require!(
+            account.last_closed_market_index == market_index,
+            SettlementError::BankruptcySideUnknown
+        );
+        let haircut_long_side = !account.last_closed_was_long;
 
-        // Apply to cross-margin collateral with overflow protection
-        let new_collateral = balance.collateral
-            .checked_add(net_settlement)
+        let deficit = account
+            .collateral
+            .checked_neg()
             .ok_or(SettlementError::CalculationOverflow)?;
 
-        // Update collateral (can be negative in cross-margin)
-        // Note: Allowing negative balance for cross-margin
-        // In production, you'd check against maintenance margin requirements
-        balance.collateral = new_collateral;
+        let insurance_used = deficit.min(insurance_fund.balance.max(0));
+        insurance_fund.balance = insurance_fund
+            .balance
+            .checked_sub(insurance_used)
+            .ok_or(SettlementError::CalculationOverflow)?;
 
-        // ============================================================================
-        // STATE UPDATES (PREVENT DOUBLE-COUNTING)
-        // ============================================================================
+        let socialized_amount = deficit
+            .checked_sub(insurance_used)
+            .ok_or(SettlementError::CalculationOverflow)?;
 
-        // Update position state to reflect settlement
-        // This prevents double-counting on subsequent settlements
-        position.entry_price = oracle_price; // Mark-to-market
-        position.last_funding_rate = funding_rate; // Update funding checkpoint
+        if socialized_amount > 0 {
+            let haircut = checked_from_i128(socialized_amount)?;
+            if haircut_long_side {
+                market.long_funding_index = market
+                    .long_funding_index
+                    .checked_add(haircut)
+                    .ok_or(SettlementError::CalculationOverflow)?;
+            } else {
+                market.short_funding_index = market
+                    .short_funding_index
+                    .checked_sub(haircut)
+                    .ok_or(SettlementError::CalculationOverflow)?;
+            }
+        }
 
-        // ============================================================================
-        // EMIT EVENT FOR MONITORING
-        // ============================================================================
+        account.collateral = 0;
 
-        emit!(SettlementEvent {
-            position_key: position.key(),
-            oracle_price,
-            funding_rate,
-            unrealized_pnl,
-            funding_payment,
-            net_settlement,
-            new_collateral,
+        emit!(BankruptcyResolvedEvent {
+            account_key: account.key(),
+            market_index,
+            deficit,
+            insurance_used,
+            socialized_amount,
         });
 
         Ok(())
     }
 }
 
-// ============================================================================
-// ACCOUNT STRUCTURES
-// ============================================================================
-
-#[account]
-pub struct Position {
-    /// Signed position size (positive = long, negative = short)
-    pub size: i64,
-    /// Entry price (used as reference for PnL calculation)
-    pub entry_price: i64,
-    /// Last settled funding rate (prevents double-counting)
-    pub last_funding_rate: i64,
-}
-
-#[account]
-pub struct UserBalance {
-    /// Shared cross-margin collateral (can be negative)
-    pub collateral: i128,
-}
-
 // ============================================================================
 // CONTEXT
 // ============================================================================
 
 #[derive(Accounts)]
 pub struct SettleCrossMargin<'info> {
+    /// The cross-margin account being settled. Its active positions' markets
+    /// and oracles are supplied via `remaining_accounts`, in slot order.
     #[account(mut)]
-    pub position: Account<'info, Position>,
-
-    #[account(mut)]
-    pub balance: Account<'info, UserBalance>,
+    pub account: Account<'info, CrossMarginAccount>,
 
     /// Authority that can trigger settlement (e.g., user or keeper)
     pub authority: Signer<'info>,
 }
 
-// ============================================================================
-// ERRORS
-// ============================================================================
+#[derive(Accounts)]
+pub struct Liquidate<'info> {
+    /// The undercollateralized cross-margin account being reduced.
+    #[account(mut)]
+    pub account: Account<'info, CrossMarginAccount>,
 
-#[error_code]
-pub enum SettlementError {
-    #[msg("Oracle price must be positive")]
-    InvalidOraclePrice,
+    /// The liquidator's cross-margin account, credited the liquidation fee.
+    #[account(mut)]
+    pub liquidator_account: Account<'info, CrossMarginAccount>,
 
-    #[msg("Entry price must be positive")]
-    InvalidEntryPrice,
+    /// Market config holding the `StablePriceModel` and `OracleConfig` used to
+    /// derive and validate the liquidation mark.
+    #[account(mut)]
+    pub market: Account<'info, Market>,
 
-    #[msg("Calculation resulted in overflow or underflow")]
-    CalculationOverflow,
+    /// CHECK: layout is provider-specific (Pyth or Switchboard) and parsed in
+    /// `oracle::read_oracle_price` per `market.oracle_config.provider`.
+    #[account(address = market.oracle @ SettlementError::InvalidOraclePrice)]
+    pub oracle: AccountInfo<'info>,
 
-    #[msg("Funding rate is outside acceptable bounds")]
-    FundingRateOutOfBounds,
+    /// Anyone may liquidate an account once its maintenance health is negative.
+    pub liquidator: Signer<'info>,
 }
 
-// ============================================================================
-// EVENTS
-// ============================================================================
+#[derive(Accounts)]
+pub struct ResolveBankruptcy<'info> {
+    /// The bankrupt cross-margin account being zeroed out.
+    #[account(mut)]
+    pub account: Account<'info, CrossMarginAccount>,
+
+    /// Market whose funding index absorbs whatever the insurance fund can't cover.
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    /// Must match `market.insurance_fund`; drawn down before any loss is socialized.
+    #[account(mut, address = market.insurance_fund @ SettlementError::InsuranceFundMismatch)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
 
-#[event]
-pub struct SettlementEvent {
-    pub position_key: Pubkey,
-    pub oracle_price: i64,
-    pub funding_rate: i64,
-    pub unrealized_pnl: i128,
-    pub funding_payment: i128,
-    pub net_settlement: i128,
-    pub new_collateral: i128,
+    /// Anyone may resolve a bankrupt account once it has nothing left to liquidate.
+    pub keeper: Signer<'info>,
 }
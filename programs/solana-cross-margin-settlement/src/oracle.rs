@@ -0,0 +1,80 @@
+use anchor_lang::prelude::*;
+use fixed::types::I80F48;
+
+use crate::errors::SettlementError;
+use crate::state::{OracleConfig, OracleProvider};
+
+/// A price read out of an on-chain oracle account, normalized to the
+/// provider-agnostic shape `settle_cross_margin` validates against.
+pub struct OraclePrice {
+    pub price: I80F48,
+    pub confidence: I80F48,
+    pub slot: u64,
+}
+
+/// Reads and normalizes a price from an oracle account, dispatching on
+/// `config.provider` to support both Pyth and Switchboard layouts.
+pub fn read_oracle_price(oracle_ai: &AccountInfo, config: &OracleConfig) -> Result<OraclePrice> {
+    match config.provider {
+        OracleProvider::Pyth => read_pyth_price(oracle_ai),
+        OracleProvider::Switchboard => read_switchboard_price(oracle_ai),
+    }
+}
+
+fn read_pyth_price(oracle_ai: &AccountInfo) -> Result<OraclePrice> {
+    let data = oracle_ai
+        .try_borrow_data()
+        .map_err(|_| error!(SettlementError::InvalidOraclePrice))?;
+    let price_account = pyth_sdk_solana::state::load_price_account(&data)
+        .map_err(|_| error!(SettlementError::InvalidOraclePrice))?;
+
+    require!(price_account.agg.price > 0, SettlementError::InvalidOraclePrice);
+
+    let expo = price_account.expo;
+    let price = scale_by_expo(price_account.agg.price, expo)?;
+    let confidence = scale_by_expo(price_account.agg.conf as i64, expo)?;
+
+    Ok(OraclePrice {
+        price,
+        confidence,
+        slot: price_account.agg.pub_slot,
+    })
+}
+
+fn read_switchboard_price(oracle_ai: &AccountInfo) -> Result<OraclePrice> {
+    let aggregator = switchboard_v2::AggregatorAccountData::new(oracle_ai)
+        .map_err(|_| error!(SettlementError::InvalidOraclePrice))?;
+    let round = aggregator.latest_confirmed_round;
+
+    let price = decimal_to_fixed(round.result)?;
+    let confidence = decimal_to_fixed(round.std_deviation)?;
+    require!(price.is_positive(), SettlementError::InvalidOraclePrice);
+
+    Ok(OraclePrice {
+        price,
+        confidence,
+        slot: round.round_open_slot,
+    })
+}
+
+/// Applies a Pyth-style base-10 exponent to a raw integer price/confidence.
+fn scale_by_expo(value: i64, expo: i32) -> Result<I80F48> {
+    let value = I80F48::from_num(value);
+    if expo >= 0 {
+        value
+            .checked_mul(I80F48::from_num(10i64.pow(expo as u32)))
+            .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+    } else {
+        value
+            .checked_div(I80F48::from_num(10i64.pow((-expo) as u32)))
+            .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+    }
+}
+
+/// Converts a Switchboard `SwitchboardDecimal` (mantissa + scale) to `I80F48`.
+fn decimal_to_fixed(decimal: switchboard_v2::SwitchboardDecimal) -> Result<I80F48> {
+    let mantissa = I80F48::from_num(decimal.mantissa);
+    mantissa
+        .checked_div(I80F48::from_num(10i128.pow(decimal.scale)))
+        .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+}
@@ -0,0 +1,379 @@
+use anchor_lang::prelude::*;
+use fixed::types::I80F48;
+
+use crate::errors::SettlementError;
+
+// ============================================================================
+// ACCOUNT STRUCTURES
+// ============================================================================
+
+/// Number of concurrent per-market positions a `CrossMarginAccount` can hold.
+/// Mirrors Mango v4's fixed-size perp position slots so the account stays a
+/// single fixed-layout PDA instead of a growable collection.
+pub const MAX_PERP_POSITIONS: usize = 8;
+
+/// Sentinel `market_index` marking a `PerpPosition` slot as free.
+pub const FREE_MARKET_INDEX: u16 = u16::MAX;
+
+/// One market's worth of exposure inside a `CrossMarginAccount`. Slots are
+/// reused: a slot with `market_index == FREE_MARKET_INDEX` is available for
+/// `CrossMarginAccount::open_position` to claim.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct PerpPosition {
+    /// Which `Market` this slot is open against, or `FREE_MARKET_INDEX` if unused.
+    pub market_index: u16,
+    /// Signed position size (positive = long, negative = short)
+    pub size: i64,
+    /// Entry price (used as reference for PnL calculation)
+    pub entry_price: I80F48,
+    /// Snapshot of `market.long_funding_index` as of this position's last
+    /// settlement, charged against when `size > 0`.
+    pub cumulative_funding_long: i128,
+    /// Snapshot of `market.short_funding_index` as of this position's last
+    /// settlement, charged against when `size < 0`.
+    pub cumulative_funding_short: i128,
+    /// Running total of mark-to-market PnL that has actually been paid into
+    /// `account.collateral`, as opposed to PnL still unrealized against
+    /// `entry_price`.
+    pub realized_pnl_native: i128,
+    /// Remaining native-unit budget for settling mark-to-market PnL. Refreshed
+    /// downward each settlement to never exceed the position's current
+    /// unrealized PnL, so a position can't be drained faster than it's
+    /// actually losing (or paid out faster than it's actually winning).
+    pub recurring_settle_limit: i128,
+    /// Remaining native-unit budget for settling funding/fee-style PnL, which
+    /// (unlike mark-to-market PnL) is eligible to settle immediately in full
+    /// once budget allows.
+    pub oneshot_settle_limit: i128,
+    /// `size` as of the last time `recurring_settle_limit`/`oneshot_settle_limit`
+    /// were refreshed to a fresh budget. There is no separate open/increase
+    /// instruction in this program, so `settle_position` treats any mismatch
+    /// against the current `size` (including a position fresh off
+    /// `PerpPosition::default()`, where this starts at 0) as "just opened or
+    /// resized" and re-derives both budgets from the current notional instead
+    /// of leaving them at whatever the downward-only ratchet last left them at.
+    pub settle_limit_size: i64,
+}
+
+impl Default for PerpPosition {
+    fn default() -> Self {
+        Self {
+            market_index: FREE_MARKET_INDEX,
+            size: 0,
+            entry_price: I80F48::ZERO,
+            cumulative_funding_long: 0,
+            cumulative_funding_short: 0,
+            realized_pnl_native: 0,
+            recurring_settle_limit: 0,
+            oneshot_settle_limit: 0,
+            settle_limit_size: 0,
+        }
+    }
+}
+
+impl PerpPosition {
+    pub fn is_active(&self) -> bool {
+        self.market_index != FREE_MARKET_INDEX
+    }
+}
+
+#[account]
+pub struct CrossMarginAccount {
+    /// Shared cross-margin collateral in native units (can be negative).
+    pub collateral: i128,
+    /// Number of slots in `positions` currently in use.
+    pub in_use_count: u8,
+    /// Fixed-size table of per-market exposure; free slots have
+    /// `market_index == FREE_MARKET_INDEX` and are reused by later opens.
+    pub positions: [PerpPosition; MAX_PERP_POSITIONS],
+    /// `market_index` of the position `liquidate` most recently closed to
+    /// zero on this account, or `FREE_MARKET_INDEX` if none has been closed
+    /// yet. Outlives `close_position_if_flat` freeing that slot, so
+    /// `resolve_bankruptcy` can derive which side to haircut for a market
+    /// from data instead of trusting caller-supplied input.
+    pub last_closed_market_index: u16,
+    /// Sign of that position's size just before `liquidate` zeroed it
+    /// (`true` = was long). Only meaningful when `last_closed_market_index`
+    /// matches the market being resolved.
+    pub last_closed_was_long: bool,
+}
+
+impl CrossMarginAccount {
+    /// Returns the slot already open against `market_index`, if any.
+    pub fn position_mut(&mut self, market_index: u16) -> Option<&mut PerpPosition> {
+        self.positions.iter_mut().find(|p| p.market_index == market_index)
+    }
+
+    /// Returns the slot already open against `market_index`, allocating a
+    /// free one if this account has no exposure to that market yet.
+    pub fn open_position(&mut self, market_index: u16) -> Result<&mut PerpPosition> {
+        if let Some(i) = self.positions.iter().position(|p| p.market_index == market_index) {
+            return Ok(&mut self.positions[i]);
+        }
+
+        let i = self
+            .positions
+            .iter()
+            .position(|p| !p.is_active())
+            .ok_or(SettlementError::NoFreePositionSlot)?;
+        self.positions[i] = PerpPosition {
+            market_index,
+            ..PerpPosition::default()
+        };
+        self.in_use_count = self
+            .in_use_count
+            .checked_add(1)
+            .ok_or(SettlementError::CalculationOverflow)?;
+        Ok(&mut self.positions[i])
+    }
+
+    /// Frees `market_index`'s slot once it's been fully closed out, so a
+    /// later `open_position` call for a different market can reuse it.
+    pub fn close_position_if_flat(&mut self, market_index: u16) -> Result<()> {
+        if let Some(i) = self.positions.iter().position(|p| p.market_index == market_index) {
+            if self.positions[i].size == 0 {
+                self.positions[i] = PerpPosition::default();
+                self.in_use_count = self
+                    .in_use_count
+                    .checked_sub(1)
+                    .ok_or(SettlementError::CalculationOverflow)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Per-market configuration referenced by settlement instructions.
+#[account]
+pub struct Market {
+    /// Index this market is referenced by from `PerpPosition::market_index`.
+    pub market_index: u16,
+    /// Smoothed mark price model, used instead of the raw oracle print for PnL.
+    pub stable_price_model: StablePriceModel,
+    /// The oracle account `settle_cross_margin` is required to read from.
+    pub oracle: Pubkey,
+    /// Confidence-band and staleness limits applied to that oracle.
+    pub oracle_config: OracleConfig,
+
+    /// Cumulative funding paid by longs, in native-units-per-position-unit.
+    /// `settle_cross_margin` charges a position the delta since its last
+    /// snapshot rather than trusting a caller-supplied per-call funding rate.
+    /// Advancing this index (e.g. a keeper crank pushing it forward each
+    /// funding period from the perp-to-spot price spread) is out of scope for
+    /// this module and not implemented here; the only writer in this program
+    /// is `resolve_bankruptcy`'s one-time socialized-loss haircut, which
+    /// raises it.
+    pub long_funding_index: I80F48,
+    /// Cumulative funding paid by shorts, tracked separately from
+    /// `long_funding_index` since funding need not be symmetric when the
+    /// market is imbalanced. Same out-of-scope caveat as `long_funding_index`
+    /// applies: nothing in this program advances it upward.
+    pub short_funding_index: I80F48,
+
+    /// Asset weight applied to net-positive exposure when checking health
+    /// against new settlements/withdrawals (stricter than `maint_asset_weight`).
+    pub init_asset_weight: I80F48,
+    /// Asset weight applied to net-positive exposure when checking whether an
+    /// already-open account is still healthy enough to avoid liquidation.
+    pub maint_asset_weight: I80F48,
+    /// Liability weight applied to net-negative exposure for new settlements/withdrawals.
+    pub init_liab_weight: I80F48,
+    /// Liability weight applied to net-negative exposure for the maintenance check.
+    pub maint_liab_weight: I80F48,
+    /// Fraction of the closed notional paid to the liquidator as an incentive.
+    pub liquidation_fee: I80F48,
+
+    /// The `InsuranceFund` `resolve_bankruptcy` is required to draw down for
+    /// this market's bankruptcies.
+    pub insurance_fund: Pubkey,
+    /// Enable flag gating `resolve_bankruptcy` for this market, so insurance
+    /// coverage must be explicitly opted into per market.
+    pub group_insurance_fund: bool,
+}
+
+/// Shared backstop drawn down by `resolve_bankruptcy` before any loss is
+/// socialized across a market's solvent counterparties.
+#[account]
+pub struct InsuranceFund {
+    /// Native-unit balance available to cover bankrupt accounts' deficits.
+    pub balance: i128,
+}
+
+// ============================================================================
+// ORACLE CONFIG
+// ============================================================================
+
+/// Which on-chain layout `oracle` should be decoded as.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleProvider {
+    Pyth,
+    Switchboard,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct OracleConfig {
+    pub provider: OracleProvider,
+    /// Max allowed `confidence / price` ratio before settlement is rejected.
+    pub conf_filter: I80F48,
+    /// Max allowed `current_slot - oracle_slot` before settlement is rejected.
+    pub max_staleness_slots: u64,
+}
+
+// ============================================================================
+// STABLE PRICE MODEL
+// ============================================================================
+
+/// Number of time-weighted interval averages kept to derive the "delay price".
+pub const DELAY_PRICE_BUFFER_LEN: usize = 24;
+
+pub const DEFAULT_DELAY_INTERVAL_SECONDS: u32 = 3600;
+
+/// A manipulation-resistant mark price derived from a raw oracle feed.
+///
+/// Incoming oracle prices are first folded into a running, time-weighted
+/// "delay price" that only moves by a bounded amount per
+/// `delay_interval_seconds`. The externally visible `stable_price` then
+/// chases the delay price, itself bounded per interval by
+/// `stable_growth_limit`. This means a single manipulated oracle print can
+/// only move `stable_price` by a small, bounded amount no matter how extreme
+/// the print is.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct StablePriceModel {
+    /// The smoothed mark price used in place of the raw oracle for PnL.
+    pub stable_price: I80F48,
+    /// Ring buffer of time-weighted interval averages.
+    pub delay_prices: [I80F48; DELAY_PRICE_BUFFER_LEN],
+    /// Index of the next slot to write in `delay_prices`.
+    pub delay_price_index: u8,
+    /// Accumulator of `price * elapsed_seconds` for the in-progress interval.
+    pub delay_accumulator_price: I80F48,
+    /// Accumulator of elapsed seconds for the in-progress interval.
+    pub delay_accumulator_time: u32,
+    /// Length in seconds of one delay interval.
+    pub delay_interval_seconds: u32,
+    /// Max fractional move of the delay price per interval, e.g. `0.06` for 6%.
+    pub delay_growth_limit: I80F48,
+    /// Max fractional move of `stable_price` per interval, e.g. `0.0006`.
+    pub stable_growth_limit: I80F48,
+    /// Unix timestamp of the last update, used to weight accumulated prices.
+    pub last_update_ts: i64,
+}
+
+impl StablePriceModel {
+    /// Seeds the model so that every ring buffer slot starts at
+    /// `initial_price`, i.e. `stable_price` tracks the oracle immediately
+    /// rather than ramping up from zero.
+    pub fn new(initial_price: I80F48, now_ts: i64) -> Self {
+        Self {
+            stable_price: initial_price,
+            delay_prices: [initial_price; DELAY_PRICE_BUFFER_LEN],
+            delay_price_index: 0,
+            delay_accumulator_price: I80F48::ZERO,
+            delay_accumulator_time: 0,
+            delay_interval_seconds: DEFAULT_DELAY_INTERVAL_SECONDS,
+            delay_growth_limit: I80F48::from_num(0.06),
+            stable_growth_limit: I80F48::from_num(0.0006),
+            last_update_ts: now_ts,
+        }
+    }
+
+    fn delay_price(&self) -> Result<I80F48> {
+        let mut sum = I80F48::ZERO;
+        for price in self.delay_prices.iter() {
+            sum = sum
+                .checked_add(*price)
+                .ok_or(SettlementError::CalculationOverflow)?;
+        }
+        sum.checked_div(I80F48::from_num(DELAY_PRICE_BUFFER_LEN))
+            .ok_or_else(|| error!(SettlementError::CalculationOverflow))
+    }
+
+    /// Folds a fresh oracle print into the model and returns the current
+    /// `stable_price` to be used as the settlement mark.
+    pub fn update(&mut self, oracle_price: I80F48, now_ts: i64) -> Result<I80F48> {
+        require!(oracle_price.is_positive(), SettlementError::InvalidOraclePrice);
+
+        // First observation (a zero-initialized model with no stable price
+        // yet): seed the whole model from this print.
+        if !self.stable_price.is_positive() {
+            *self = Self::new(oracle_price, now_ts);
+            return Ok(self.stable_price);
+        }
+
+        let elapsed = now_ts
+            .checked_sub(self.last_update_ts)
+            .ok_or(SettlementError::CalculationOverflow)?;
+        require!(elapsed >= 0, SettlementError::CalculationOverflow);
+        let elapsed = elapsed as u32;
+
+        self.delay_accumulator_price = self
+            .delay_accumulator_price
+            .checked_add(
+                oracle_price
+                    .checked_mul(I80F48::from_num(elapsed))
+                    .ok_or(SettlementError::CalculationOverflow)?,
+            )
+            .ok_or(SettlementError::CalculationOverflow)?;
+        self.delay_accumulator_time = self
+            .delay_accumulator_time
+            .checked_add(elapsed)
+            .ok_or(SettlementError::CalculationOverflow)?;
+        self.last_update_ts = now_ts;
+
+        // A single call can span several whole intervals (e.g. a settlement
+        // coming in long after the last one), so drain the accumulator one
+        // `delay_interval_seconds` slice at a time rather than collapsing
+        // however many intervals elapsed into a single growth-limit step.
+        // Removing a slice valued at the accumulator's own time-weighted
+        // average (this print plus any leftover from earlier calls) leaves
+        // that average unchanged, so each slice reuses it while still being
+        // clamped (and compounding) against the growth limit once per interval.
+        while self.delay_accumulator_time >= self.delay_interval_seconds {
+            let interval_avg = self
+                .delay_accumulator_price
+                .checked_div(I80F48::from_num(self.delay_accumulator_time))
+                .ok_or(SettlementError::CalculationOverflow)?;
+
+            let prev_delay_price = self.delay_price()?;
+            let clamped_interval_avg =
+                clamp_growth(prev_delay_price, interval_avg, self.delay_growth_limit)?;
+
+            let idx = self.delay_price_index as usize % DELAY_PRICE_BUFFER_LEN;
+            self.delay_prices[idx] = clamped_interval_avg;
+            self.delay_price_index = ((idx + 1) % DELAY_PRICE_BUFFER_LEN) as u8;
+
+            let slice_time = self.delay_interval_seconds;
+            let slice_price = interval_avg
+                .checked_mul(I80F48::from_num(slice_time))
+                .ok_or(SettlementError::CalculationOverflow)?;
+            self.delay_accumulator_price = self
+                .delay_accumulator_price
+                .checked_sub(slice_price)
+                .ok_or(SettlementError::CalculationOverflow)?;
+            self.delay_accumulator_time -= slice_time;
+
+            let new_delay_price = self.delay_price()?;
+            self.stable_price =
+                clamp_growth(self.stable_price, new_delay_price, self.stable_growth_limit)?;
+        }
+
+        Ok(self.stable_price)
+    }
+}
+
+/// Clamps `target` to within `prev +/- |prev| * limit`.
+fn clamp_growth(prev: I80F48, target: I80F48, limit: I80F48) -> Result<I80F48> {
+    let bound = prev
+        .checked_abs()
+        .and_then(|p| p.checked_mul(limit))
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    let lower = prev
+        .checked_sub(bound)
+        .ok_or(SettlementError::CalculationOverflow)?;
+    let upper = prev
+        .checked_add(bound)
+        .ok_or(SettlementError::CalculationOverflow)?;
+
+    Ok(target.clamp(lower, upper))
+}
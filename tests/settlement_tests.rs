@@ -1,108 +1,143 @@
 use anchor_lang::prelude::*;
-use solana_cross_margin_settlement::{Position, UserBalance};
+use fixed::types::I80F48;
+use solana_cross_margin_settlement::health;
+use solana_cross_margin_settlement::{
+    clamp_magnitude, settle_position_at_price, CrossMarginAccount, Market, OracleConfig,
+    OracleProvider, PerpPosition, StablePriceModel, FREE_MARKET_INDEX, MAX_PERP_POSITIONS,
+};
 
 #[cfg(test)]
 mod settlement_tests {
     use super::*;
 
-    /// Helper function to create a test position
-    fn create_position(size: i64, entry_price: i64, last_funding_rate: i64) -> Position {
-        Position {
+    /// Helper function to create a test position. `funding_snapshot` seeds
+    /// both the long and short cumulative-funding checkpoints, since most
+    /// tests only ever exercise one side.
+    fn create_position(size: i64, entry_price: i64, funding_snapshot: i64) -> PerpPosition {
+        PerpPosition {
+            market_index: 0,
             size,
-            entry_price,
-            last_funding_rate,
+            entry_price: I80F48::from_num(entry_price),
+            cumulative_funding_long: funding_snapshot as i128,
+            cumulative_funding_short: funding_snapshot as i128,
+            realized_pnl_native: 0,
+            recurring_settle_limit: i128::MAX,
+            oneshot_settle_limit: i128::MAX,
+            settle_limit_size: size,
         }
     }
 
-    /// Helper function to create a test balance
-    fn create_balance(collateral: i128) -> UserBalance {
-        UserBalance { collateral }
+    /// Helper function to create a test market with a seeded stable price and
+    /// permissive oracle/weight configuration, for tests that exercise
+    /// `settle_position_at_price` directly.
+    fn create_market(stable_price: i64) -> Market {
+        Market {
+            market_index: 0,
+            stable_price_model: StablePriceModel::new(I80F48::from_num(stable_price), 0),
+            oracle: Pubkey::default(),
+            oracle_config: OracleConfig {
+                provider: OracleProvider::Pyth,
+                conf_filter: I80F48::from_num(0.02),
+                max_staleness_slots: 100,
+            },
+            long_funding_index: I80F48::ZERO,
+            short_funding_index: I80F48::ZERO,
+            init_asset_weight: I80F48::from_num(0.8),
+            maint_asset_weight: I80F48::from_num(0.9),
+            init_liab_weight: I80F48::from_num(1.2),
+            maint_liab_weight: I80F48::from_num(1.1),
+            liquidation_fee: I80F48::from_num(0.01),
+            insurance_fund: Pubkey::default(),
+            group_insurance_fund: false,
+        }
+    }
+
+    /// Helper function to create a test cross-margin account with no open positions.
+    fn create_account(collateral: i128) -> CrossMarginAccount {
+        CrossMarginAccount {
+            collateral,
+            in_use_count: 0,
+            positions: [PerpPosition::default(); MAX_PERP_POSITIONS],
+            last_closed_market_index: FREE_MARKET_INDEX,
+            last_closed_was_long: false,
+        }
     }
 
     #[test]
     fn test_long_position_profit() {
         // Long position (size > 0) with price increase
         let mut position = create_position(100, 1000, 0);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
         // Oracle price increases to 1100
-        let oracle_price = 1100;
-        let funding_rate = 0;
+        let oracle_price = I80F48::from_num(1100);
 
         // Expected PnL: (1100 - 1000) * 100 = 10000
-        // Expected funding: (0 - 0) * 100 = 0
-        // Net settlement: 10000 - 0 = 10000
+        // Net settlement: 10000
         // New collateral: 10000 + 10000 = 20000
 
         // Simulate settlement (manual calculation since we can't run actual Anchor program here)
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        let funding_delta = funding_rate - position.last_funding_rate;
-        let funding_payment = (funding_delta as i128) * (position.size as i128);
-        let net_settlement = unrealized_pnl - funding_payment;
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let net_settlement = unrealized_pnl;
 
-        balance.collateral += net_settlement;
+        account.collateral += net_settlement.to_num::<i128>();
         position.entry_price = oracle_price;
-        position.last_funding_rate = funding_rate;
 
-        assert_eq!(balance.collateral, 20000);
-        assert_eq!(position.entry_price, 1100);
+        assert_eq!(account.collateral, 20000);
+        assert_eq!(position.entry_price, I80F48::from_num(1100));
     }
 
     #[test]
     fn test_long_position_loss() {
         // Long position with price decrease
         let mut position = create_position(100, 1000, 0);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
-        let oracle_price = 900; // Price drops
-        let funding_rate = 0;
+        let oracle_price = I80F48::from_num(900); // Price drops
 
         // Expected PnL: (900 - 1000) * 100 = -10000
         // Net settlement: -10000
         // New collateral: 10000 - 10000 = 0
 
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        let funding_payment = 0;
-        let net_settlement = unrealized_pnl - funding_payment;
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let net_settlement = unrealized_pnl;
 
-        balance.collateral += net_settlement;
+        account.collateral += net_settlement.to_num::<i128>();
 
-        assert_eq!(balance.collateral, 0);
+        assert_eq!(account.collateral, 0);
     }
 
     #[test]
     fn test_short_position_profit() {
         // Short position (size < 0) with price decrease
         let mut position = create_position(-100, 1000, 0);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
-        let oracle_price = 900; // Price drops (good for short)
-        let funding_rate = 0;
+        let oracle_price = I80F48::from_num(900); // Price drops (good for short)
 
         // Expected PnL: (900 - 1000) * (-100) = -100 * -100 = 10000
         // Net settlement: 10000
         // New collateral: 10000 + 10000 = 20000
 
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        let funding_payment = 0;
-        let net_settlement = unrealized_pnl - funding_payment;
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let net_settlement = unrealized_pnl;
 
-        balance.collateral += net_settlement;
+        account.collateral += net_settlement.to_num::<i128>();
 
-        assert_eq!(balance.collateral, 20000);
+        assert_eq!(account.collateral, 20000);
     }
 
     #[test]
     fn test_funding_payment_long() {
-        // Long position paying positive funding
+        // Long position paying positive funding off the market's long funding index
         let mut position = create_position(100, 1000, 10);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
-        let oracle_price = 1000; // No price change
-        let funding_rate = 20; // Funding increased
+        let oracle_price = I80F48::from_num(1000); // No price change
+        let long_funding_index = I80F48::from_num(20); // Index increased since last snapshot
 
         // Expected PnL: 0
         // Expected funding: (20 - 10) * 100 = 1000
@@ -110,110 +145,110 @@ mod settlement_tests {
         // New collateral: 10000 - 1000 = 9000
 
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        let funding_delta = funding_rate - position.last_funding_rate;
-        let funding_payment = (funding_delta as i128) * (position.size as i128);
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let funding_delta = long_funding_index - I80F48::from_num(position.cumulative_funding_long);
+        let funding_payment = funding_delta * I80F48::from_num(position.size);
         let net_settlement = unrealized_pnl - funding_payment;
 
-        balance.collateral += net_settlement;
-        position.last_funding_rate = funding_rate;
+        account.collateral += net_settlement.to_num::<i128>();
+        position.cumulative_funding_long = long_funding_index.to_num::<i128>();
 
-        assert_eq!(balance.collateral, 9000);
-        assert_eq!(position.last_funding_rate, 20);
+        assert_eq!(account.collateral, 9000);
+        assert_eq!(position.cumulative_funding_long, 20);
     }
 
     #[test]
     fn test_double_settlement_prevention() {
         // Test that settling twice doesn't double-count PnL
         let mut position = create_position(100, 1000, 0);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
-        let oracle_price = 1100;
-        let funding_rate = 0;
+        let oracle_price = I80F48::from_num(1100);
 
         // First settlement
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        balance.collateral += unrealized_pnl;
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        account.collateral += unrealized_pnl.to_num::<i128>();
         position.entry_price = oracle_price; // Update entry price
 
-        assert_eq!(balance.collateral, 20000);
+        assert_eq!(account.collateral, 20000);
 
         // Second settlement with same oracle price
         // Should result in 0 PnL because entry_price was updated
         let price_delta_2 = oracle_price - position.entry_price;
-        let unrealized_pnl_2 = (price_delta_2 as i128) * (position.size as i128);
-        balance.collateral += unrealized_pnl_2;
+        let unrealized_pnl_2 = price_delta_2 * I80F48::from_num(position.size);
+        account.collateral += unrealized_pnl_2.to_num::<i128>();
 
-        assert_eq!(balance.collateral, 20000); // No change
-        assert_eq!(unrealized_pnl_2, 0);
+        assert_eq!(account.collateral, 20000); // No change
+        assert_eq!(unrealized_pnl_2, I80F48::ZERO);
     }
 
     #[test]
     fn test_negative_collateral_allowed() {
         // Cross-margin allows negative collateral
-        let mut position = create_position(100, 1000, 0);
-        let mut balance = create_balance(5000);
+        let position = create_position(100, 1000, 0);
+        let mut account = create_account(5000);
 
-        let oracle_price = 900; // Large loss
-        let funding_rate = 0;
+        let oracle_price = I80F48::from_num(900); // Large loss
 
         // Expected PnL: (900 - 1000) * 100 = -10000
         // Net settlement: -10000
         // New collateral: 5000 - 10000 = -5000
 
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
         let net_settlement = unrealized_pnl;
 
-        balance.collateral += net_settlement;
+        account.collateral += net_settlement.to_num::<i128>();
 
-        assert_eq!(balance.collateral, -5000); // Negative allowed in cross-margin
+        assert_eq!(account.collateral, -5000); // Negative allowed in cross-margin
     }
 
     #[test]
     fn test_zero_position_size() {
-        // Settling a position with size 0 should be safe
+        // Settling a position with size 0 should be safe, and should still
+        // fast-forward both funding checkpoints so a later open doesn't
+        // inherit a stale index.
         let mut position = create_position(0, 1000, 0);
-        let mut balance = create_balance(10000);
+        let account = create_account(10000);
 
-        let oracle_price = 1100;
-        let funding_rate = 10;
+        let long_funding_index = I80F48::from_num(10);
+        let short_funding_index = I80F48::from_num(10);
 
-        // With size = 0, both PnL and funding should be 0
         if position.size == 0 {
-            position.last_funding_rate = funding_rate;
+            position.cumulative_funding_long = long_funding_index.to_num::<i128>();
+            position.cumulative_funding_short = short_funding_index.to_num::<i128>();
             // No collateral change
         }
 
-        assert_eq!(balance.collateral, 10000); // No change
-        assert_eq!(position.last_funding_rate, funding_rate); // Funding rate updated
+        assert_eq!(account.collateral, 10000); // No change
+        assert_eq!(position.cumulative_funding_long, 10);
+        assert_eq!(position.cumulative_funding_short, 10);
     }
 
     #[test]
     fn test_large_position_overflow_safety() {
-        // Test that i128 is used to prevent overflow
+        // Test that I80F48 checked ops are used to prevent overflow
         let position = create_position(1_000_000_000, 1000, 0); // 1 billion units
-        let oracle_price = 2000; // 1000 point move
+        let oracle_price = I80F48::from_num(2000); // 1000 point move
 
-        // This would overflow i64 but should work with i128
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128)
-            .checked_mul(position.size as i128)
-            .expect("Should not overflow with i128");
+        let unrealized_pnl = price_delta
+            .checked_mul(I80F48::from_num(position.size))
+            .expect("Should not overflow with I80F48");
 
         // 1000 * 1_000_000_000 = 1_000_000_000_000
-        assert_eq!(unrealized_pnl, 1_000_000_000_000);
+        assert_eq!(unrealized_pnl, I80F48::from_num(1_000_000_000_000i128));
     }
 
     #[test]
     fn test_combined_pnl_and_funding() {
         // Test settlement with both PnL and funding
         let mut position = create_position(100, 1000, 5);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
-        let oracle_price = 1050; // Price increase
-        let funding_rate = 15; // Funding increase
+        let oracle_price = I80F48::from_num(1050); // Price increase
+        let long_funding_index = I80F48::from_num(15); // Funding index increase
 
         // Expected PnL: (1050 - 1000) * 100 = 5000
         // Expected funding: (15 - 5) * 100 = 1000
@@ -221,24 +256,24 @@ mod settlement_tests {
         // New collateral: 10000 + 4000 = 14000
 
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        let funding_delta = funding_rate - position.last_funding_rate;
-        let funding_payment = (funding_delta as i128) * (position.size as i128);
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let funding_delta = long_funding_index - I80F48::from_num(position.cumulative_funding_long);
+        let funding_payment = funding_delta * I80F48::from_num(position.size);
         let net_settlement = unrealized_pnl - funding_payment;
 
-        balance.collateral += net_settlement;
+        account.collateral += net_settlement.to_num::<i128>();
 
-        assert_eq!(balance.collateral, 14000);
+        assert_eq!(account.collateral, 14000);
     }
 
     #[test]
     fn test_negative_funding_rate() {
-        // Test with negative funding (shorts pay longs)
+        // Test with a negative funding index delta (shorts pay longs)
         let mut position = create_position(100, 1000, 5);
-        let mut balance = create_balance(10000);
+        let mut account = create_account(10000);
 
-        let oracle_price = 1000;
-        let funding_rate = -5; // Negative funding
+        let oracle_price = I80F48::from_num(1000);
+        let long_funding_index = I80F48::from_num(-5); // Index moved negative
 
         // Expected PnL: 0
         // Expected funding: (-5 - 5) * 100 = -1000
@@ -246,13 +281,475 @@ mod settlement_tests {
         // New collateral: 10000 + 1000 = 11000
 
         let price_delta = oracle_price - position.entry_price;
-        let unrealized_pnl = (price_delta as i128) * (position.size as i128);
-        let funding_delta = funding_rate - position.last_funding_rate;
-        let funding_payment = (funding_delta as i128) * (position.size as i128);
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let funding_delta = long_funding_index - I80F48::from_num(position.cumulative_funding_long);
+        let funding_payment = funding_delta * I80F48::from_num(position.size);
+        let net_settlement = unrealized_pnl - funding_payment;
+
+        account.collateral += net_settlement.to_num::<i128>();
+
+        assert_eq!(account.collateral, 11000);
+    }
+
+    #[test]
+    fn test_fractional_funding_rate() {
+        // Fixed-point funding indices can represent the dominant real-world
+        // case: small fractional per-hour rates like 0.01%.
+        let position = create_position(100, 1000, 0);
+        let account = create_account(10000);
+
+        let oracle_price = I80F48::from_num(1000);
+        let long_funding_index = I80F48::from_num(0.0001); // 0.01%
+
+        let price_delta = oracle_price - position.entry_price;
+        let unrealized_pnl = price_delta * I80F48::from_num(position.size);
+        let funding_delta = long_funding_index - I80F48::from_num(position.cumulative_funding_long);
+        let funding_payment = funding_delta * I80F48::from_num(position.size);
         let net_settlement = unrealized_pnl - funding_payment;
 
-        balance.collateral += net_settlement;
+        // funding_payment = 0.0001 * 100 = 0.01, so the fixed-point
+        // net_settlement carries that sub-unit amount rather than truncating
+        // to zero before it's rounded into the native-unit collateral.
+        assert_eq!(net_settlement, I80F48::ZERO - I80F48::from_num(0.01));
+        let _ = account;
+    }
+
+    #[test]
+    fn test_oracle_confidence_ratio_rejected() {
+        // Mirrors the `confidence / price > conf_filter` check performed in
+        // `settle_cross_margin` before a price is accepted.
+        let price = I80F48::from_num(100);
+        let confidence = I80F48::from_num(5); // 5% of price
+        let conf_filter = I80F48::from_num(0.02); // max 2% allowed
+
+        let conf_ratio = confidence.checked_div(price).unwrap();
+        assert!(conf_ratio > conf_filter, "wide confidence band should be rejected");
+    }
+
+    #[test]
+    fn test_oracle_staleness_rejected() {
+        // Mirrors the `current_slot - oracle_slot > max_staleness_slots` check.
+        let oracle_slot: u64 = 1000;
+        let current_slot: u64 = 1200;
+        let max_staleness_slots: u64 = 100;
+
+        let staleness = current_slot.saturating_sub(oracle_slot);
+        assert!(staleness > max_staleness_slots, "stale price should be rejected");
+    }
+
+    #[test]
+    fn test_stable_price_seeds_from_first_print() {
+        // The first update should seed stable_price directly from the oracle
+        // print rather than ramping up from zero.
+        let mut model = StablePriceModel::new(I80F48::ZERO, 0);
+        let stable_price = model.update(I80F48::from_num(1000), 1).unwrap();
+
+        assert_eq!(stable_price, I80F48::from_num(1000));
+        assert_eq!(model.stable_price, I80F48::from_num(1000));
+    }
+
+    #[test]
+    fn test_stable_price_clamps_single_bad_print() {
+        // A single wild oracle print should only move stable_price by a
+        // bounded amount, even once a full delay interval has elapsed.
+        let mut model = StablePriceModel::new(I80F48::from_num(1000), 0);
+
+        // Push one whole interval of a 10x price spike.
+        let stable_price = model
+            .update(I80F48::from_num(10_000), model.delay_interval_seconds as i64)
+            .unwrap();
+
+        // stable_growth_limit defaults to 0.06%, so the move off of 1000
+        // must be tiny relative to the 10x spike.
+        assert!(stable_price < I80F48::from_num(1100), "stable_price moved too far: {stable_price}");
+        assert!(stable_price > I80F48::from_num(1000));
+    }
+
+    #[test]
+    fn test_stable_price_tracks_sustained_move_over_time() {
+        // Over many intervals of a sustained new price, stable_price should
+        // eventually converge toward it.
+        let mut model = StablePriceModel::new(I80F48::from_num(1000), 0);
+        let interval = model.delay_interval_seconds as i64;
+
+        let mut ts = 0;
+        let mut stable_price = model.stable_price;
+        for _ in 0..500 {
+            ts += interval;
+            stable_price = model.update(I80F48::from_num(1100), ts).unwrap();
+        }
+
+        assert_eq!(stable_price, I80F48::from_num(1100));
+    }
+
+    #[test]
+    fn test_stable_price_catches_up_across_several_missed_intervals() {
+        // A single call spanning many whole intervals (e.g. the first
+        // settlement after a long gap) should apply the stable/delay growth
+        // limits once per elapsed interval, not collapse them into one step.
+        let mut model = StablePriceModel::new(I80F48::from_num(1000), 0);
+        let interval = model.delay_interval_seconds as i64;
+
+        let one_shot = {
+            let mut m = model;
+            m.update(I80F48::from_num(1100), interval).unwrap()
+        };
+        let stepped = {
+            let mut m = model;
+            let mut ts = 0;
+            let mut price = m.stable_price;
+            for _ in 0..10 {
+                ts += interval;
+                price = m.update(I80F48::from_num(1100), ts).unwrap();
+            }
+            price
+        };
+        let caught_up = model.update(I80F48::from_num(1100), interval * 10).unwrap();
+
+        assert!(
+            caught_up > one_shot,
+            "10 missed intervals should move stable_price further than a single interval: {caught_up} vs {one_shot}"
+        );
+        assert_eq!(caught_up, stepped, "one call spanning N intervals should match N separate calls");
+    }
+
+    #[test]
+    fn test_health_healthy_long_position() {
+        // A long position with collateral fully covering a discounted
+        // notional should be healthy.
+        let health = health::compute_health(
+            I80F48::from_num(1000),         // collateral
+            100,                             // size
+            I80F48::from_num(10),            // mark_price -> notional = 1000
+            I80F48::from_num(0.9),           // asset_weight
+            I80F48::from_num(1.1),           // liab_weight
+            I80F48::ZERO,                    // other_weighted_notional
+        )
+        .unwrap();
+
+        // 1000*0.9 + 1000*0.9 ~= 1800; 0.9 has no exact I80F48 representation,
+        // so the expected value is built from the same fixed-point weight.
+        let asset_weight = I80F48::from_num(0.9);
+        assert_eq!(
+            health,
+            I80F48::from_num(1000) * asset_weight + I80F48::from_num(1000) * asset_weight
+        );
+    }
+
+    #[test]
+    fn test_health_unhealthy_short_position() {
+        // A short position that moved against the account uses the liability
+        // weight on its negative notional, making health worse than the raw sum.
+        let health = health::compute_health(
+            I80F48::from_num(100),    // collateral
+            -100,                      // size (short)
+            I80F48::from_num(10),      // mark_price -> notional = -1000
+            I80F48::from_num(0.9),
+            I80F48::from_num(1.1),
+            I80F48::ZERO,
+        )
+        .unwrap();
+
+        // 100*0.9 + (-1000)*1.1 = 90 - 1100 ~= -1010; neither weight is exact
+        // in I80F48, so the expected value is built from the same fixed-point weights.
+        let expected = I80F48::from_num(100) * I80F48::from_num(0.9)
+            + I80F48::from_num(-1000) * I80F48::from_num(1.1);
+        assert_eq!(health, expected);
+    }
+
+    #[test]
+    fn test_weighted_position_notional_excludes_collateral() {
+        // `weighted_position_notional` is used to sum exposure across many
+        // markets without double-counting the account's single shared
+        // collateral, unlike `compute_health` which folds both together.
+        let notional = health::weighted_position_notional(
+            100,
+            I80F48::from_num(10), // notional = 1000
+            I80F48::from_num(0.9),
+            I80F48::from_num(1.1),
+        )
+        .unwrap();
+
+        // 1000*0.9 ~= 900; 0.9 has no exact I80F48 representation.
+        assert_eq!(notional, I80F48::from_num(1000) * I80F48::from_num(0.9));
+    }
 
-        assert_eq!(balance.collateral, 11000);
+    #[test]
+    fn test_find_liquidation_amount_stops_once_healthy() {
+        // A short position whose liability-weighted notional outweighs
+        // collateral should only be reduced by as much as needed to bring
+        // maintenance health back to >= 0, not liquidated in full.
+        let step = health::find_liquidation_amount(
+            -100,                        // position_size (short)
+            I80F48::from_num(1000),      // entry_price
+            I80F48::from_num(10_000),    // collateral
+            I80F48::from_num(1000),      // mark_price (no PnL on the closed amount)
+            I80F48::ZERO,                // liquidation_fee
+            I80F48::from_num(1),         // maint_asset_weight
+            I80F48::from_num(2),         // maint_liab_weight
+            I80F48::ZERO,                // other_weighted_notional
+            100,                         // max_base_amount
+        )
+        .unwrap();
+
+        let health_after = health::compute_health(
+            step.new_collateral,
+            step.new_size,
+            I80F48::from_num(1000),
+            I80F48::from_num(1),
+            I80F48::from_num(2),
+            I80F48::ZERO,
+        )
+        .unwrap();
+
+        assert!(step.base_amount > 0);
+        assert!(step.base_amount < 100, "should not liquidate the whole position");
+        assert!(health_after >= I80F48::ZERO);
+    }
+
+    #[test]
+    fn test_liquidation_charges_own_loss_to_account_and_only_fee_to_liquidator() {
+        // A long position closed at a loss (entry 1000, mark 900) must have
+        // that loss land on the liquidated account itself, not the
+        // liquidator - the liquidator is only ever owed the fee. `other`
+        // exposure is pinned deeply negative so no amount of reducing this
+        // position brings maintenance health back to >= 0, forcing a full
+        // liquidation of `max_base_amount` with fully deterministic math.
+        let step = health::find_liquidation_amount(
+            100,                          // position_size (long)
+            I80F48::from_num(1000),       // entry_price
+            I80F48::ZERO,                 // collateral
+            I80F48::from_num(900),        // mark_price (a loss)
+            I80F48::from_num(0.01),       // liquidation_fee (1%)
+            I80F48::from_num(1),          // maint_asset_weight
+            I80F48::from_num(1),          // maint_liab_weight
+            I80F48::from_num(-1_000_000), // other_weighted_notional (unfixably unhealthy)
+            50,                           // max_base_amount
+        )
+        .unwrap();
+
+        // closed_pnl = 50 * (900 - 1000) = -5000, fee = 50 * 900 * 0.01 ~= 450
+        // (0.01 has no exact I80F48 representation, so the fee is built from
+        // the same fixed-point rate).
+        let fee = I80F48::from_num(50 * 900) * I80F48::from_num(0.01);
+        assert_eq!(step.base_amount, 50, "should liquidate the full amount when health never recovers");
+        assert_eq!(step.transfer_to_liquidator, fee, "liquidator should only ever receive the fee");
+        assert_eq!(
+            step.new_collateral,
+            I80F48::from_num(-5000) - fee,
+            "the account's own realized loss (and the fee) must come out of its own collateral"
+        );
+    }
+
+    #[test]
+    fn test_clamp_magnitude_shrinks_to_budget() {
+        // A loss larger than the available budget is capped at the budget,
+        // keeping the original sign.
+        let clamped =
+            clamp_magnitude(I80F48::from_num(-10_000), I80F48::from_num(4_000)).unwrap();
+        assert_eq!(clamped, I80F48::from_num(-4_000));
+
+        let clamped =
+            clamp_magnitude(I80F48::from_num(10_000), I80F48::from_num(4_000)).unwrap();
+        assert_eq!(clamped, I80F48::from_num(4_000));
+    }
+
+    #[test]
+    fn test_clamp_magnitude_passes_through_when_within_budget() {
+        let clamped = clamp_magnitude(I80F48::from_num(500), I80F48::from_num(4_000)).unwrap();
+        assert_eq!(clamped, I80F48::from_num(500));
+    }
+
+    #[test]
+    fn test_funding_settles_one_shot_up_to_budget() {
+        // Funding is one-shot-settleable: it can pay out in full as soon as
+        // there's budget, unlike mark-to-market PnL.
+        let mut position = create_position(100, 1000, 0);
+        position.oneshot_settle_limit = 500;
+
+        let funding_payment = I80F48::from_num(1_000); // owed, but budget is only 500
+        let settled_funding =
+            clamp_magnitude(funding_payment, I80F48::from_num(position.oneshot_settle_limit))
+                .unwrap();
+
+        assert_eq!(settled_funding, I80F48::from_num(500));
+    }
+
+    #[test]
+    fn test_recurring_limit_only_ratchets_down() {
+        // The recurring budget tracks the position's current unrealized PnL
+        // rather than a separately-accumulated realized amount, so it only
+        // ever shrinks toward what's actually still unsettled.
+        let mut position = create_position(100, 1000, 0);
+        position.recurring_settle_limit = 10_000;
+
+        let unrealized_pnl = I80F48::from_num(3_000); // less than the stale limit
+        let unsettled_pnl = unrealized_pnl.abs();
+        if I80F48::from_num(position.recurring_settle_limit) > unsettled_pnl {
+            position.recurring_settle_limit = unsettled_pnl.to_num::<i128>();
+        }
+
+        assert_eq!(position.recurring_settle_limit, 3_000);
+    }
+
+    #[test]
+    fn test_settle_position_at_price_fresh_open_can_settle_real_pnl() {
+        // A position straight off `PerpPosition::default()` (the only way a
+        // real position is ever created, since there's no open/increase
+        // instruction) starts with zero settle-limit budget. The very first
+        // settlement with real PnL must still succeed by refreshing the
+        // budget from the position's current notional, instead of tripping
+        // `SettleLimitExceeded` forever.
+        let mut position = PerpPosition {
+            market_index: 0,
+            size: 100,
+            entry_price: I80F48::from_num(1000),
+            ..PerpPosition::default()
+        };
+        assert_eq!(position.recurring_settle_limit, 0);
+        assert_eq!(position.oneshot_settle_limit, 0);
+
+        let mut market = create_market(1000);
+
+        let settlement =
+            settle_position_at_price(&mut position, &mut market, I80F48::from_num(1100), I80F48::from_num(1100))
+                .unwrap();
+
+        // (1100 - 1000) * 100 = 10_000, fully settled since the budget was
+        // just refreshed to the position's notional (110_000).
+        assert_eq!(settlement.unrealized_pnl, I80F48::from_num(10_000));
+        assert_eq!(settlement.net_settlement, I80F48::from_num(10_000));
+        assert_eq!(position.entry_price, I80F48::from_num(1100));
+        assert_eq!(position.settle_limit_size, 100);
+    }
+
+    #[test]
+    fn test_settle_position_at_price_refreshes_only_on_size_change() {
+        // Once a position has settled once at a given size, its settle-limit
+        // budget should keep ratcheting downward on subsequent calls at that
+        // same size, rather than being refreshed back up every time.
+        let mut position = PerpPosition {
+            market_index: 0,
+            size: 100,
+            entry_price: I80F48::from_num(1000),
+            ..PerpPosition::default()
+        };
+        let mut market = create_market(1000);
+
+        settle_position_at_price(&mut position, &mut market, I80F48::from_num(1100), I80F48::from_num(1100))
+            .unwrap();
+        let limit_after_first = position.recurring_settle_limit;
+        assert_eq!(position.settle_limit_size, 100);
+
+        // Second settlement at the same size: no new PnL, so the recurring
+        // budget should ratchet down to zero rather than refresh back up.
+        let settlement =
+            settle_position_at_price(&mut position, &mut market, I80F48::from_num(1100), I80F48::from_num(1100))
+                .unwrap();
+        assert_eq!(settlement.unrealized_pnl, I80F48::ZERO);
+        assert_eq!(position.recurring_settle_limit, 0);
+        assert!(position.recurring_settle_limit <= limit_after_first);
+    }
+
+    #[test]
+    fn test_open_position_allocates_and_reuses_free_slot() {
+        let mut account = create_account(0);
+
+        account.open_position(3).unwrap();
+        assert_eq!(account.in_use_count, 1);
+
+        // Opening the same market index again returns the existing slot
+        // rather than allocating a second one.
+        account.open_position(3).unwrap();
+        assert_eq!(account.in_use_count, 1);
+
+        assert!(account.position_mut(3).is_some());
+    }
+
+    #[test]
+    fn test_open_position_fails_when_all_slots_full() {
+        let mut account = create_account(0);
+
+        for market_index in 0..MAX_PERP_POSITIONS as u16 {
+            account.open_position(market_index).unwrap();
+        }
+
+        assert!(account.open_position(MAX_PERP_POSITIONS as u16).is_err());
+    }
+
+    #[test]
+    fn test_close_position_if_flat_frees_slot_for_reuse() {
+        let mut account = create_account(0);
+        account.open_position(1).unwrap();
+
+        account.close_position_if_flat(1).unwrap();
+        assert_eq!(account.in_use_count, 0);
+
+        // The freed slot can now back a different market.
+        account.open_position(2).unwrap();
+        assert_eq!(account.in_use_count, 1);
+        assert!(account.position_mut(1).is_none());
+    }
+
+    #[test]
+    fn test_close_position_if_flat_noop_when_size_nonzero() {
+        let mut account = create_account(0);
+        account.open_position(1).unwrap();
+        account.position_mut(1).unwrap().size = 50;
+
+        account.close_position_if_flat(1).unwrap();
+
+        assert_eq!(account.in_use_count, 1);
+        assert!(account.position_mut(1).is_some());
+    }
+
+    #[test]
+    fn test_bankruptcy_fully_covered_by_insurance_fund() {
+        // Mirrors `resolve_bankruptcy`'s insurance-fund draw-down: a deficit
+        // smaller than the fund's balance is covered in full, with nothing
+        // left to socialize.
+        let deficit: i128 = 4_000;
+        let insurance_fund_balance: i128 = 10_000;
+
+        let insurance_used = deficit.min(insurance_fund_balance.max(0));
+        let socialized_amount = deficit - insurance_used;
+
+        assert_eq!(insurance_used, 4_000);
+        assert_eq!(socialized_amount, 0);
+    }
+
+    #[test]
+    fn test_bankruptcy_partially_socialized_when_fund_exhausted() {
+        // When the insurance fund can't cover the whole deficit, the
+        // remainder is socialized against the opposing side's funding index.
+        let deficit: i128 = 4_000;
+        let insurance_fund_balance: i128 = 1_500;
+
+        let insurance_used = deficit.min(insurance_fund_balance.max(0));
+        let socialized_amount = deficit - insurance_used;
+
+        assert_eq!(insurance_used, 1_500);
+        assert_eq!(socialized_amount, 2_500);
+
+        // The haircut moves the winning side's funding index against it -
+        // here shorts, whose index is lowered by the socialized amount (a
+        // winning long side's index is raised instead) - shrinking what it
+        // settles for on its next call into `settle_cross_margin`.
+        let short_funding_index = I80F48::from_num(100);
+        let haircut_short_funding_index = short_funding_index - I80F48::from_num(socialized_amount);
+        assert_eq!(haircut_short_funding_index, I80F48::from_num(-2_400));
+    }
+
+    #[test]
+    fn test_not_bankrupt_when_position_still_open() {
+        // `resolve_bankruptcy` must refuse an account that still has
+        // liquidatable size, even if its collateral is negative.
+        let mut account = create_account(-500);
+        account.open_position(0).unwrap();
+        account.position_mut(0).unwrap().size = 10;
+
+        let has_open_size = account.positions.iter().any(|p| p.is_active() && p.size != 0);
+        assert!(account.collateral < 0);
+        assert!(has_open_size, "position with nonzero size blocks bankruptcy resolution");
     }
 }